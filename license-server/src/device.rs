@@ -0,0 +1,172 @@
+// license-server/src/device.rs
+//
+// OAuth-style device authorization flow so a headless or installed app can
+// activate without manually copying token files: the client requests a
+// code, a human approves it out-of-band using the short `user_code`, and
+// the client polls `/api/device/token` until that approval lands.
+
+use rand_core::{OsRng, RngCore};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+const CODE_TTL_SECS: u64 = 10 * 60;
+const POLL_INTERVAL_SECS: u64 = 5;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+enum DeviceStatus {
+    Pending,
+    Approved { user_id: String },
+}
+
+struct DeviceAuth {
+    status: DeviceStatus,
+    expires_at: u64,
+    last_polled_at: u64,
+}
+
+pub struct IssuedCode {
+    pub device_code: String,
+    pub user_code: String,
+    pub interval: u64,
+    pub expires_in: u64,
+}
+
+pub enum PollOutcome {
+    Pending,
+    SlowDown,
+    Approved { user_id: String },
+    Expired,
+}
+
+// In-memory store for outstanding device codes, keyed both by the opaque
+// `device_code` the client polls with and the short `user_code` a human
+// types into the approval page.
+pub struct DeviceStore {
+    by_device_code: Mutex<HashMap<String, DeviceAuth>>,
+    device_code_by_user_code: Mutex<HashMap<String, String>>,
+}
+
+impl Default for DeviceStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DeviceStore {
+    pub fn new() -> Self {
+        DeviceStore {
+            by_device_code: Mutex::new(HashMap::new()),
+            device_code_by_user_code: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn create_code(&self) -> IssuedCode {
+        let device_code = Uuid::new_v4().to_string();
+        let user_code = Self::generate_user_code();
+        let expires_at = now() + CODE_TTL_SECS;
+
+        self.by_device_code.lock().unwrap().insert(
+            device_code.clone(),
+            DeviceAuth {
+                status: DeviceStatus::Pending,
+                expires_at,
+                last_polled_at: 0,
+            },
+        );
+        self.device_code_by_user_code
+            .lock()
+            .unwrap()
+            .insert(user_code.clone(), device_code.clone());
+
+        IssuedCode {
+            device_code,
+            user_code,
+            interval: POLL_INTERVAL_SECS,
+            expires_in: CODE_TTL_SECS,
+        }
+    }
+
+    // Short, human-typeable code from an alphabet that avoids visually
+    // ambiguous characters (no 0/O/1/I), formatted like "WXYZ-2345".
+    fn generate_user_code() -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+        let mut raw = [0u8; 8];
+        OsRng.fill_bytes(&mut raw);
+        let code: String = raw
+            .iter()
+            .map(|b| ALPHABET[*b as usize % ALPHABET.len()] as char)
+            .collect();
+        format!("{}-{}", &code[..4], &code[4..])
+    }
+
+    /// Called by the human-facing approval step, keyed by the short
+    /// `user_code`. Returns `false` if the code is unknown or expired.
+    pub fn approve(&self, user_code: &str, user_id: String) -> bool {
+        let Some(device_code) = self
+            .device_code_by_user_code
+            .lock()
+            .unwrap()
+            .get(user_code)
+            .cloned()
+        else {
+            return false;
+        };
+
+        let mut by_device_code = self.by_device_code.lock().unwrap();
+        let Some(entry) = by_device_code.get_mut(&device_code) else {
+            return false;
+        };
+        if now() > entry.expires_at {
+            return false;
+        }
+
+        entry.status = DeviceStatus::Approved { user_id };
+        true
+    }
+
+    /// Polls the status of `device_code`, enforcing the minimum poll
+    /// interval between calls. Does *not* consume the entry on `Approved` —
+    /// the caller hasn't actually issued a token yet at this point (that
+    /// can still fail, e.g. the seat limit), so the entry must survive
+    /// until `consume` is called after a token is issued.
+    pub fn poll(&self, device_code: &str) -> PollOutcome {
+        let mut by_device_code = self.by_device_code.lock().unwrap();
+        let Some(entry) = by_device_code.get_mut(device_code) else {
+            return PollOutcome::Expired;
+        };
+
+        let t = now();
+        if t > entry.expires_at {
+            return PollOutcome::Expired;
+        }
+        if t < entry.last_polled_at + POLL_INTERVAL_SECS {
+            return PollOutcome::SlowDown;
+        }
+        entry.last_polled_at = t;
+
+        match &entry.status {
+            DeviceStatus::Pending => PollOutcome::Pending,
+            DeviceStatus::Approved { user_id } => PollOutcome::Approved {
+                user_id: user_id.clone(),
+            },
+        }
+    }
+
+    /// Consumes `device_code` once a token has actually been issued for
+    /// it, so a later poll (e.g. a replayed request with a different
+    /// fingerprint) can't mint a second token. Call this only after
+    /// activation and signing succeed; on failure (e.g. the seat limit was
+    /// hit) leave the code alone so the legitimate caller can retry
+    /// without restarting the whole device-code/approval flow.
+    pub fn consume(&self, device_code: &str) {
+        self.by_device_code.lock().unwrap().remove(device_code);
+    }
+}
@@ -0,0 +1,114 @@
+// license-server/src/keys.rs
+//
+// Persistent Ed25519 key management with rotation support. Keys are stored
+// on disk as raw 32-byte seeds (hex-encoded) keyed by a short `kid`, so a
+// server restart doesn't silently invalidate every outstanding token. The
+// most recently loaded/added key is the "active" key used for signing new
+// tokens; older keys stay around purely for verification of tokens issued
+// before a rotation.
+
+use ed25519_dalek::{SigningKey, VerifyingKey};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use uuid::Uuid;
+
+const KEY_STORE_PATH: &str = "license_server_keys.json";
+
+pub struct KeyEntry {
+    pub kid: String,
+    pub signing_key: SigningKey,
+    pub verifying_key: VerifyingKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredKey {
+    kid: String,
+    seed_hex: String,
+}
+
+pub struct KeyStore {
+    keys: Vec<KeyEntry>,
+}
+
+impl KeyStore {
+    /// Loads keys from `KEY_STORE_PATH`, generating and persisting a fresh
+    /// keypair the first time the server runs.
+    pub fn load_or_create() -> Self {
+        let keys = match fs::read_to_string(KEY_STORE_PATH) {
+            Ok(content) => {
+                let stored: Vec<StoredKey> =
+                    serde_json::from_str(&content).expect("corrupt key store file");
+                stored
+                    .into_iter()
+                    .map(|s| {
+                        let seed_bytes =
+                            hex::decode(&s.seed_hex).expect("invalid seed hex in key store");
+                        let seed: [u8; 32] =
+                            seed_bytes.try_into().expect("seed must be 32 bytes");
+                        let signing_key = SigningKey::from_bytes(&seed);
+                        let verifying_key = signing_key.verifying_key();
+                        KeyEntry {
+                            kid: s.kid,
+                            signing_key,
+                            verifying_key,
+                        }
+                    })
+                    .collect()
+            }
+            Err(_) => {
+                let entry = Self::generate_entry();
+                Self::persist(std::slice::from_ref(&entry));
+                vec![entry]
+            }
+        };
+
+        KeyStore { keys }
+    }
+
+    fn generate_entry() -> KeyEntry {
+        let mut csprng = OsRng;
+        let signing_key = SigningKey::generate(&mut csprng);
+        let verifying_key = signing_key.verifying_key();
+        let kid = Uuid::new_v4().to_string()[..8].to_string();
+        KeyEntry {
+            kid,
+            signing_key,
+            verifying_key,
+        }
+    }
+
+    fn persist(keys: &[KeyEntry]) {
+        let stored: Vec<StoredKey> = keys
+            .iter()
+            .map(|k| StoredKey {
+                kid: k.kid.clone(),
+                seed_hex: hex::encode(k.signing_key.to_bytes()),
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&stored).unwrap();
+        fs::write(KEY_STORE_PATH, json).expect("failed to persist key store");
+    }
+
+    /// The key new tokens are signed with (the last one loaded/generated).
+    pub fn active(&self) -> &KeyEntry {
+        self.keys.last().expect("key store is never empty")
+    }
+
+    /// All keys, for exposing via `/api/public-key`.
+    pub fn all(&self) -> &[KeyEntry] {
+        &self.keys
+    }
+
+    /// Generates a new signing key, appends it so it becomes the active
+    /// key, and persists the updated store. Older keys are kept so tokens
+    /// they already signed keep verifying via their `kid`. Returns the
+    /// new key's `kid`.
+    pub fn rotate(&mut self) -> String {
+        let entry = Self::generate_entry();
+        let kid = entry.kid.clone();
+        self.keys.push(entry);
+        Self::persist(&self.keys);
+        kid
+    }
+}
@@ -1,31 +1,61 @@
 // license-server/src/main.rs
 // Run with: cargo run
 
+mod device;
+mod keys;
+mod store;
+
 use actix_web::{web, App, HttpServer, HttpResponse, Result};
-use ed25519_dalek::{SigningKey, Signer, VerifyingKey};
-use rand_core::OsRng;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use device::{DeviceStore, PollOutcome};
+use ed25519_dalek::Signer;
+use keys::{KeyEntry, KeyStore};
 use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
-use std::collections::HashMap;
-use std::sync::Mutex;
+use store::{InMemoryStore, LicenseStore, RedisStore};
+use uuid::Uuid;
 
+// Standard JWT header. `alg` is always "EdDSA" today, but keeping it in the
+// header (rather than hardcoding it everywhere) means we can support other
+// algorithms later without changing the token shape. `kid` identifies which
+// of the server's keys signed this token, so verifiers can pick the right
+// one even after a key rotation.
 #[derive(Serialize, Deserialize, Clone)]
-struct TrialToken {
-    user_id: String,
-    issued_at: u64,
-    expires_at: u64,
+struct JwtHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
+
+// Registered + custom claims carried inside the signed JWT payload.
+// `fingerprint` node-locks the token to the machine it was issued for.
+#[derive(Serialize, Deserialize, Clone)]
+struct TrialClaims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+    nbf: u64,
+    jti: String,
+    tier: String,
+    fingerprint: String,
 }
 
 #[derive(Serialize)]
 struct TokenResponse {
     token: String,
-    signature: String,
     message: String,
 }
 
 #[derive(Deserialize)]
 struct IssueRequest {
     user_id: String,
+    fingerprint: String,
+}
+
+#[derive(Deserialize)]
+struct UserIdRequest {
+    user_id: String,
 }
 
 #[derive(Deserialize)]
@@ -33,17 +63,72 @@ struct CheckRequest {
     user_id: String,
 }
 
-#[derive(Serialize)]
-struct CheckResponse {
+// Signed payload carried inside `SignedResponse::payload` for
+// `/api/trial/check`. The trial binary verifies the signature over this
+// JSON string before trusting any of it. `grace_until` doubles as a signed
+// "check receipt": the binary persists it and relies on it (instead of an
+// editable local timestamp) to bound how long it can run offline.
+#[derive(Serialize, Deserialize)]
+struct RevocationCheck {
+    user_id: String,
     revoked: bool,
+    checked_at: u64,
+    grace_until: u64,
+}
+
+// How long a trial binary may run offline after a successful online check,
+// before it must check in again. The trial binary has no grace window of
+// its own; it reads this deadline back from the signed receipt.
+const OFFLINE_GRACE_SECS: u64 = 24 * 60 * 60;
+
+// A small "certificate revocation list" the trial binary can cache and
+// check entirely offline: the signature proves authenticity, and
+// `next_update` lets the binary detect a stale cache.
+#[derive(Serialize, Deserialize)]
+struct Crl {
+    revoked_user_ids: Vec<String>,
+    issued_at: u64,
+    next_update: u64,
+}
+
+const CRL_VALIDITY_SECS: u64 = 60 * 60; // re-fetch at least hourly
+
+// Wraps any JSON-serializable payload together with a signature over its
+// exact serialized bytes, so the receiver can verify authenticity without
+// needing to re-derive an identical serialization itself.
+#[derive(Serialize)]
+struct SignedResponse {
+    payload: String,
+    signature: String,
+    kid: String,
     message: String,
 }
 
-// Simple in-memory "database" for demo
+fn sign_response(key: &KeyEntry, payload: &impl Serialize, message: String) -> SignedResponse {
+    let payload_json = serde_json::to_string(payload).unwrap();
+    let signature = key.signing_key.sign(payload_json.as_bytes());
+
+    SignedResponse {
+        payload: payload_json,
+        signature: hex::encode(signature.to_bytes()),
+        kid: key.kid.clone(),
+        message,
+    }
+}
+
+// How many distinct machine fingerprints a single user_id may have active
+// at once, i.e. the number of floating seats per license.
+const MAX_SEATS_PER_USER: usize = 3;
+
+// Revocations and seat activations live behind `LicenseStore` (see
+// `store.rs`) rather than directly on `AppState`, so a single Redis-backed
+// store can be shared by multiple server instances behind a load balancer.
 struct AppState {
-    signing_key: SigningKey,
-    verifying_key: VerifyingKey,
-    revoked_users: Mutex<HashMap<String, bool>>,
+    // Behind a `Mutex` (rather than a plain `KeyStore`) so `/api/admin/rotate-key`
+    // can append a new active key at runtime without restarting the server.
+    keys: Mutex<KeyStore>,
+    device_store: DeviceStore,
+    store: Arc<dyn LicenseStore>,
 }
 
 fn current_timestamp() -> u64 {
@@ -53,114 +138,384 @@ fn current_timestamp() -> u64 {
         .as_secs()
 }
 
+// Shared by the direct issue endpoint and the device-flow token endpoint.
+fn new_trial_claims(user_id: String, fingerprint: String) -> TrialClaims {
+    let now = current_timestamp();
+    TrialClaims {
+        sub: user_id,
+        iat: now,
+        exp: now + (14 * 24 * 60 * 60), // 14 days in seconds
+        nbf: now,
+        jti: Uuid::new_v4().to_string(),
+        tier: "trial".to_string(),
+        fingerprint,
+    }
+}
+
+// Encodes a JWT-compatible `header.payload.signature` string, signing
+// `header.payload` with the given key and stamping its `kid` into the
+// header so verifiers know which key to check against.
+fn encode_jwt(key: &KeyEntry, claims: &TrialClaims) -> String {
+    let header = JwtHeader {
+        alg: "EdDSA".to_string(),
+        typ: "JWT".to_string(),
+        kid: key.kid.clone(),
+    };
+
+    let header_b64 = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header).unwrap());
+    let claims_b64 = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap());
+    let signing_input = format!("{}.{}", header_b64, claims_b64);
+
+    let signature = key.signing_key.sign(signing_input.as_bytes());
+    let signature_b64 = URL_SAFE_NO_PAD.encode(signature.to_bytes());
+
+    format!("{}.{}", signing_input, signature_b64)
+}
+
 // POST /api/trial/issue
 async fn issue_trial(
     data: web::Json<IssueRequest>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let now = current_timestamp();
-    let token = TrialToken {
-        user_id: data.user_id.clone(),
-        issued_at: now,
-        expires_at: now + (14 * 24 * 60 * 60), // 14 days in seconds
-    };
-    
-    let token_json = serde_json::to_string(&token).unwrap();
-    let signature = state.signing_key.sign(token_json.as_bytes());
-    
-    println!("✓ Issued trial for user: {}", data.user_id);
+    if let Err(e) = state
+        .store
+        .record_activation(&data.user_id, &data.fingerprint)
+        .await
+    {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({ "error": e })));
+    }
+
+    let claims = new_trial_claims(data.user_id.clone(), data.fingerprint.clone());
+    let token = encode_jwt(state.keys.lock().unwrap().active(), &claims);
+
+    println!(
+        "✓ Issued trial for user: {} ({}/{} seats in use)",
+        data.user_id,
+        state.store.count_activations(&data.user_id).await,
+        MAX_SEATS_PER_USER
+    );
     println!("  Expires: {} seconds from now", 14 * 24 * 60 * 60);
-    
+
     Ok(HttpResponse::Ok().json(TokenResponse {
-        token: token_json,
-        signature: hex::encode(signature.to_bytes()),
+        token,
         message: format!("Trial issued for {} (14 days)", data.user_id),
     }))
 }
 
 // GET /api/trial/check?user_id=xxx
+//
+// The response is signed so a MITM (or a fake server on 127.0.0.1:8081)
+// can't force `revoked: false` by forging an unsigned reply.
 async fn check_revocation(
     query: web::Query<CheckRequest>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    let revoked = state.revoked_users
-        .lock()
-        .unwrap()
-        .get(&query.user_id)
-        .copied()
-        .unwrap_or(false);
-    
+    let revoked = match state.store.is_revoked(&query.user_id).await {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            eprintln!("⚠️  Revocation store unavailable: {}", e);
+            return Ok(HttpResponse::ServiceUnavailable()
+                .json(serde_json::json!({ "error": "revocation_store_unavailable" })));
+        }
+    };
+
     let message = if revoked {
         format!("User {} has been revoked", query.user_id)
     } else {
         format!("User {} is active", query.user_id)
     };
-    
-    Ok(HttpResponse::Ok().json(CheckResponse {
+
+    let checked_at = current_timestamp();
+    let check = RevocationCheck {
+        user_id: query.user_id.clone(),
         revoked,
+        checked_at,
+        grace_until: checked_at + OFFLINE_GRACE_SECS,
+    };
+
+    Ok(HttpResponse::Ok().json(sign_response(
+        state.keys.lock().unwrap().active(),
+        &check,
         message,
-    }))
+    )))
+}
+
+// GET /api/trial/crl
+//
+// Returns a signed, timestamped revocation list the trial binary can cache
+// and check while offline, instead of trusting an editable local file.
+async fn get_crl(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let revoked_user_ids = match state.store.list_revoked().await {
+        Ok(ids) => ids,
+        Err(e) => {
+            eprintln!("⚠️  Revocation store unavailable: {}", e);
+            return Ok(HttpResponse::ServiceUnavailable()
+                .json(serde_json::json!({ "error": "revocation_store_unavailable" })));
+        }
+    };
+
+    let issued_at = current_timestamp();
+    let crl = Crl {
+        revoked_user_ids,
+        issued_at,
+        next_update: issued_at + CRL_VALIDITY_SECS,
+    };
+
+    Ok(HttpResponse::Ok().json(sign_response(
+        state.keys.lock().unwrap().active(),
+        &crl,
+        "CRL issued".to_string(),
+    )))
 }
 
 // POST /api/trial/revoke
 async fn revoke_trial(
-    data: web::Json<IssueRequest>,
+    data: web::Json<UserIdRequest>,
     state: web::Data<AppState>,
 ) -> Result<HttpResponse> {
-    state.revoked_users
-        .lock()
-        .unwrap()
-        .insert(data.user_id.clone(), true);
-    
+    state.store.revoke(&data.user_id).await;
+
     println!("✗ Revoked trial for user: {}", data.user_id);
-    
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
         "message": format!("Trial revoked for {}", data.user_id)
     })))
 }
 
+#[derive(Deserialize)]
+struct DeactivateRequest {
+    user_id: String,
+    fingerprint: String,
+}
+
+// POST /api/trial/deactivate
+//
+// Frees a seat so the same user_id can activate a different machine
+// without waiting for the token to expire.
+async fn deactivate_trial(
+    data: web::Json<DeactivateRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    let freed = state
+        .store
+        .deactivate(&data.user_id, &data.fingerprint)
+        .await;
+
+    let message = if freed {
+        format!("Freed seat for {} on this machine", data.user_id)
+    } else {
+        format!("No active seat found for {} on this machine", data.user_id)
+    };
+
+    println!("{} {}", if freed { "✓" } else { "ℹ️ " }, message);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "message": message })))
+}
+
+#[derive(Serialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    interval: u64,
+    expires_in: u64,
+}
+
+// POST /api/device/code
+async fn device_code(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let issued = state.device_store.create_code();
+
+    println!(
+        "📟 Device code issued, waiting for approval of user_code: {}",
+        issued.user_code
+    );
+
+    Ok(HttpResponse::Ok().json(DeviceCodeResponse {
+        device_code: issued.device_code,
+        user_code: issued.user_code,
+        verification_uri: "http://127.0.0.1:8081/activate".to_string(),
+        interval: issued.interval,
+        expires_in: issued.expires_in,
+    }))
+}
+
+#[derive(Deserialize)]
+struct DeviceApproveRequest {
+    user_code: String,
+    user_id: String,
+}
+
+// POST /api/device/approve
+//
+// Stands in for the human-facing approval page at `verification_uri`. In a
+// full product this would be a small web UI the user signs into; the demo
+// exposes it directly so the flow can be exercised from the command line.
+async fn device_approve(
+    data: web::Json<DeviceApproveRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    if state.device_store.approve(&data.user_code, data.user_id.clone()) {
+        println!(
+            "✓ Approved device code {} for user: {}",
+            data.user_code, data.user_id
+        );
+        Ok(HttpResponse::Ok().json(serde_json::json!({ "message": "Device approved" })))
+    } else {
+        Ok(HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "invalid_user_code" })))
+    }
+}
+
+#[derive(Deserialize)]
+struct DeviceTokenRequest {
+    device_code: String,
+    fingerprint: String,
+}
+
+// POST /api/device/token
+//
+// Polled by the client at the `interval` returned from `/api/device/code`.
+// Mirrors the standard device grant error codes so clients can reuse
+// off-the-shelf polling logic.
+async fn device_token(
+    data: web::Json<DeviceTokenRequest>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse> {
+    match state.device_store.poll(&data.device_code) {
+        PollOutcome::Pending => Ok(HttpResponse::BadRequest()
+            .json(serde_json::json!({ "error": "authorization_pending" }))),
+        PollOutcome::SlowDown => {
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "slow_down" })))
+        }
+        PollOutcome::Expired => {
+            Ok(HttpResponse::BadRequest().json(serde_json::json!({ "error": "expired_token" })))
+        }
+        PollOutcome::Approved { user_id } => {
+            if let Err(e) = state
+                .store
+                .record_activation(&user_id, &data.fingerprint)
+                .await
+            {
+                return Ok(HttpResponse::Conflict().json(serde_json::json!({ "error": e })));
+            }
+
+            let claims = new_trial_claims(user_id.clone(), data.fingerprint.clone());
+            let token = encode_jwt(state.keys.lock().unwrap().active(), &claims);
+
+            // Only now has a token actually been issued for this code, so
+            // only now do we consume it — a failed activation above leaves
+            // the code intact for the client to retry.
+            state.device_store.consume(&data.device_code);
+
+            println!(
+                "✓ Issued trial via device flow for user: {} ({}/{} seats in use)",
+                user_id,
+                state.store.count_activations(&user_id).await,
+                MAX_SEATS_PER_USER
+            );
+
+            Ok(HttpResponse::Ok().json(TokenResponse {
+                token,
+                message: format!("Trial issued for {} (14 days)", user_id),
+            }))
+        }
+    }
+}
+
 // GET /api/public-key
+//
+// Returns every active signing key, not just the current one, so binaries
+// can keep verifying tokens issued before a key rotation.
 async fn get_public_key(state: web::Data<AppState>) -> Result<HttpResponse> {
-    let public_key_bytes = state.verifying_key.to_bytes();
-    
+    let keys: Vec<_> = state
+        .keys
+        .lock()
+        .unwrap()
+        .all()
+        .iter()
+        .map(|k| {
+            serde_json::json!({
+                "kid": k.kid,
+                "public_key": hex::encode(k.verifying_key.to_bytes()),
+                "alg": "EdDSA",
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+// POST /api/admin/rotate-key
+//
+// Generates a new signing key and makes it active, without invalidating
+// tokens already signed with older keys (they keep verifying via their
+// `kid`, still returned from `/api/public-key`).
+async fn rotate_key(state: web::Data<AppState>) -> Result<HttpResponse> {
+    let kid = state.keys.lock().unwrap().rotate();
+
+    println!("🔄 Rotated signing key, new active kid: {}", kid);
+
     Ok(HttpResponse::Ok().json(serde_json::json!({
-        "public_key": hex::encode(public_key_bytes),
-        "format": "ed25519",
-        "note": "Embed this in your trial binary"
+        "message": "Signing key rotated",
+        "kid": kid,
     })))
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     println!("🔐 License Server Starting...\n");
-    
-    // Generate keypair (in production, load from secure storage!)
-    let mut csprng = OsRng;
-    let signing_key = SigningKey::generate(&mut csprng);
-    let verifying_key = signing_key.verifying_key();
-    
-    println!("📝 Your Public Key (embed this in trial binary):");
-    println!("   {}\n", hex::encode(verifying_key.to_bytes()));
-    
+
+    let keys = KeyStore::load_or_create();
+
+    println!("📝 Active signing key (embed this in trial binary):");
+    println!("   kid: {}", keys.active().kid);
+    println!("   {}\n", hex::encode(keys.active().verifying_key.to_bytes()));
+
+    let store: Arc<dyn LicenseStore> = match std::env::var("REDIS_URL") {
+        Ok(redis_url) => {
+            println!("🗄️  Revocations/activations backed by Redis at {}", redis_url);
+            Arc::new(
+                RedisStore::new(&redis_url, MAX_SEATS_PER_USER)
+                    .expect("failed to construct Redis client for REDIS_URL"),
+            )
+        }
+        Err(_) => {
+            println!("🗄️  Revocations/activations kept in memory (set REDIS_URL to share across instances)");
+            Arc::new(InMemoryStore::new(MAX_SEATS_PER_USER))
+        }
+    };
+
     let app_state = web::Data::new(AppState {
-        signing_key,
-        verifying_key,
-        revoked_users: Mutex::new(HashMap::new()),
+        keys: Mutex::new(keys),
+        device_store: DeviceStore::new(),
+        store,
     });
-    
+
     println!("🚀 Server running at http://127.0.0.1:8081\n");
     println!("Available endpoints:");
-    println!("  POST   /api/trial/issue     - Issue new trial");
-    println!("  GET    /api/trial/check     - Check revocation status");
+    println!("  POST   /api/trial/issue     - Issue new trial (node-locked)");
+    println!("  GET    /api/trial/check     - Check revocation status (signed)");
+    println!("  GET    /api/trial/crl       - Fetch signed revocation list");
     println!("  POST   /api/trial/revoke    - Revoke a trial");
-    println!("  GET    /api/public-key      - Get public key\n");
-    
+    println!("  POST   /api/trial/deactivate- Free a seat for a machine");
+    println!("  POST   /api/device/code     - Start device authorization");
+    println!("  POST   /api/device/approve  - Approve a device's user_code");
+    println!("  POST   /api/device/token    - Poll for the issued trial token");
+    println!("  GET    /api/public-key      - Get public key");
+    println!("  POST   /api/admin/rotate-key- Rotate the active signing key\n");
+
     HttpServer::new(move || {
         App::new()
             .app_data(app_state.clone())
             .route("/api/trial/issue", web::post().to(issue_trial))
             .route("/api/trial/check", web::get().to(check_revocation))
+            .route("/api/trial/crl", web::get().to(get_crl))
             .route("/api/trial/revoke", web::post().to(revoke_trial))
+            .route("/api/trial/deactivate", web::post().to(deactivate_trial))
+            .route("/api/admin/rotate-key", web::post().to(rotate_key))
+            .route("/api/device/code", web::post().to(device_code))
+            .route("/api/device/approve", web::post().to(device_approve))
+            .route("/api/device/token", web::post().to(device_token))
             .route("/api/public-key", web::get().to(get_public_key))
     })
     .bind("127.0.0.1:8081")?
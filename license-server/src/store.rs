@@ -0,0 +1,259 @@
+// license-server/src/store.rs
+//
+// Pluggable storage for revocations and per-license seat activations.
+// `InMemoryStore` is what the demo runs with by default; `RedisStore` lets
+// the same server share that state across multiple instances behind a
+// load balancer, and survive restarts.
+
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+#[async_trait]
+pub trait LicenseStore: Send + Sync {
+    // `Result`-returning, not bool/Vec, so a backend outage (e.g. Redis
+    // unreachable) propagates to the caller instead of silently reading as
+    // "nobody is revoked" — callers must fail closed, not open.
+    async fn is_revoked(&self, user_id: &str) -> Result<bool, String>;
+    async fn revoke(&self, user_id: &str);
+    async fn list_revoked(&self) -> Result<Vec<String>, String>;
+
+    /// Activates `fingerprint` for `user_id`. Idempotent if that
+    /// fingerprint is already activated; otherwise fails once the
+    /// implementation's seat limit is reached.
+    async fn record_activation(&self, user_id: &str, fingerprint: &str) -> Result<(), String>;
+    async fn count_activations(&self, user_id: &str) -> usize;
+    async fn deactivate(&self, user_id: &str, fingerprint: &str) -> bool;
+}
+
+pub struct InMemoryStore {
+    max_seats: usize,
+    revoked_users: Mutex<HashSet<String>>,
+    activations: Mutex<HashMap<String, HashSet<String>>>,
+}
+
+impl InMemoryStore {
+    pub fn new(max_seats: usize) -> Self {
+        InMemoryStore {
+            max_seats,
+            revoked_users: Mutex::new(HashSet::new()),
+            activations: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl LicenseStore for InMemoryStore {
+    async fn is_revoked(&self, user_id: &str) -> Result<bool, String> {
+        Ok(self.revoked_users.lock().unwrap().contains(user_id))
+    }
+
+    async fn revoke(&self, user_id: &str) {
+        self.revoked_users.lock().unwrap().insert(user_id.to_string());
+    }
+
+    async fn list_revoked(&self) -> Result<Vec<String>, String> {
+        Ok(self.revoked_users.lock().unwrap().iter().cloned().collect())
+    }
+
+    async fn record_activation(&self, user_id: &str, fingerprint: &str) -> Result<(), String> {
+        let mut activations = self.activations.lock().unwrap();
+        let seats = activations.entry(user_id.to_string()).or_default();
+
+        if seats.contains(fingerprint) {
+            return Ok(());
+        }
+        if seats.len() >= self.max_seats {
+            return Err(format!(
+                "Seat limit reached for {}: {} of {} seats already activated",
+                user_id,
+                seats.len(),
+                self.max_seats
+            ));
+        }
+
+        seats.insert(fingerprint.to_string());
+        Ok(())
+    }
+
+    async fn count_activations(&self, user_id: &str) -> usize {
+        self.activations
+            .lock()
+            .unwrap()
+            .get(user_id)
+            .map(|seats| seats.len())
+            .unwrap_or(0)
+    }
+
+    async fn deactivate(&self, user_id: &str, fingerprint: &str) -> bool {
+        self.activations
+            .lock()
+            .unwrap()
+            .get_mut(user_id)
+            .map(|seats| seats.remove(fingerprint))
+            .unwrap_or(false)
+    }
+}
+
+// Keys are namespaced under `license:`. Per-user revocation and activation
+// keys carry a TTL matching trial token expiry, so stale entries from
+// expired trials don't accumulate forever; the revoked-user index has no
+// TTL and is pruned by `list_revoked` instead.
+mod redis_store {
+    use super::LicenseStore;
+    use async_trait::async_trait;
+    use redis::AsyncCommands;
+
+    const TRIAL_TTL_SECS: i64 = 14 * 24 * 60 * 60;
+    const REVOKED_INDEX_KEY: &str = "license:revoked_index";
+
+    fn revoked_key(user_id: &str) -> String {
+        format!("license:revoked:{}", user_id)
+    }
+
+    fn activations_key(user_id: &str) -> String {
+        format!("license:activations:{}", user_id)
+    }
+
+    // Atomically checks the seat's membership and count against `max_seats`
+    // before adding it, so concurrent requests against different server
+    // instances can't both squeeze past the limit between separate
+    // SISMEMBER/SCARD/SADD round trips. Returns 1 if the fingerprint was
+    // newly added, 0 if it was already a member, -1 if the limit was hit.
+    const RECORD_ACTIVATION_SCRIPT: &str = r"
+        local key = KEYS[1]
+        local fingerprint = ARGV[1]
+        local max_seats = tonumber(ARGV[2])
+        local ttl = tonumber(ARGV[3])
+
+        if redis.call('SISMEMBER', key, fingerprint) == 1 then
+            return 0
+        end
+        if redis.call('SCARD', key) >= max_seats then
+            return -1
+        end
+
+        redis.call('SADD', key, fingerprint)
+        redis.call('EXPIRE', key, ttl)
+        return 1
+    ";
+
+    pub struct RedisStore {
+        client: redis::Client,
+        max_seats: usize,
+        record_activation_script: redis::Script,
+    }
+
+    impl RedisStore {
+        pub fn new(redis_url: &str, max_seats: usize) -> redis::RedisResult<Self> {
+            Ok(RedisStore {
+                client: redis::Client::open(redis_url)?,
+                max_seats,
+                record_activation_script: redis::Script::new(RECORD_ACTIVATION_SCRIPT),
+            })
+        }
+
+        async fn conn(&self) -> redis::RedisResult<redis::aio::MultiplexedConnection> {
+            self.client.get_multiplexed_async_connection().await
+        }
+    }
+
+    #[async_trait]
+    impl LicenseStore for RedisStore {
+        async fn is_revoked(&self, user_id: &str) -> Result<bool, String> {
+            let mut conn = self
+                .conn()
+                .await
+                .map_err(|e| format!("Redis connection error: {}", e))?;
+            conn.exists(revoked_key(user_id))
+                .await
+                .map_err(|e| format!("Redis error: {}", e))
+        }
+
+        async fn revoke(&self, user_id: &str) {
+            let Ok(mut conn) = self.conn().await else {
+                return;
+            };
+            let _: redis::RedisResult<()> = conn
+                .set_ex(revoked_key(user_id), 1, TRIAL_TTL_SECS as u64)
+                .await;
+            let _: redis::RedisResult<()> = conn.sadd(REVOKED_INDEX_KEY, user_id).await;
+        }
+
+        async fn list_revoked(&self) -> Result<Vec<String>, String> {
+            let mut conn = self
+                .conn()
+                .await
+                .map_err(|e| format!("Redis connection error: {}", e))?;
+            let indexed: Vec<String> = conn
+                .smembers(REVOKED_INDEX_KEY)
+                .await
+                .map_err(|e| format!("Redis error: {}", e))?;
+
+            // The index isn't TTL'd (Redis sets can't expire individual
+            // members), so only report users whose revocation key hasn't
+            // expired yet, and drop the rest from the index as we go.
+            let mut still_revoked = Vec::new();
+            for user_id in indexed {
+                if conn
+                    .exists(revoked_key(&user_id))
+                    .await
+                    .map_err(|e| format!("Redis error: {}", e))?
+                {
+                    still_revoked.push(user_id);
+                } else {
+                    let _: redis::RedisResult<()> =
+                        conn.srem(REVOKED_INDEX_KEY, &user_id).await;
+                }
+            }
+            Ok(still_revoked)
+        }
+
+        async fn record_activation(&self, user_id: &str, fingerprint: &str) -> Result<(), String> {
+            let mut conn = self
+                .conn()
+                .await
+                .map_err(|e| format!("Redis connection error: {}", e))?;
+            let key = activations_key(user_id);
+
+            let result: i64 = self
+                .record_activation_script
+                .key(&key)
+                .arg(fingerprint)
+                .arg(self.max_seats)
+                .arg(TRIAL_TTL_SECS)
+                .invoke_async(&mut conn)
+                .await
+                .map_err(|e| format!("Redis error: {}", e))?;
+
+            if result < 0 {
+                let count: usize = conn.scard(&key).await.unwrap_or(self.max_seats);
+                return Err(format!(
+                    "Seat limit reached for {}: {} of {} seats already activated",
+                    user_id, count, self.max_seats
+                ));
+            }
+            Ok(())
+        }
+
+        async fn count_activations(&self, user_id: &str) -> usize {
+            let Ok(mut conn) = self.conn().await else {
+                return 0;
+            };
+            conn.scard(activations_key(user_id)).await.unwrap_or(0)
+        }
+
+        async fn deactivate(&self, user_id: &str, fingerprint: &str) -> bool {
+            let Ok(mut conn) = self.conn().await else {
+                return false;
+            };
+            let removed: i64 = conn
+                .srem(activations_key(user_id), fingerprint)
+                .await
+                .unwrap_or(0);
+            removed > 0
+        }
+    }
+}
+
+pub use redis_store::RedisStore;
@@ -1,49 +1,105 @@
 // trial-binary/src/bin/get-license.rs
-// Run with: cargo run --bin get-license -- demo-user3
+// Run with: cargo run --bin get-license -- demo-user
+//
+// Implements the client side of the device authorization flow: request a
+// code, show the user_code for out-of-band approval, then poll until the
+// server issues a signed trial token.
 
 use serde::Deserialize;
 use std::env;
 use std::fs;
+use std::time::{Duration, Instant};
+
+#[derive(Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    interval: u64,
+    expires_in: u64,
+}
 
 #[derive(Deserialize)]
 struct LicenseResponse {
     token: String,
-    signature: String,
+}
+
+#[derive(Deserialize)]
+struct DeviceErrorResponse {
+    error: String,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args: Vec<String> = env::args().collect();
     let user_id = args.get(1).map(|s| s.as_str()).unwrap_or("demo-user");
-    
-    println!("🔄 Requesting license for: {}", user_id);
-    
+
     let client = reqwest::Client::new();
-    let response = client
-        .post("http://127.0.0.1:8081/api/trial/issue")
-        .header("Content-Type", "application/json")
-        .json(&serde_json::json!({
-            "user_id": user_id
-        }))
+
+    println!("🔄 Requesting a device code...");
+    let code: DeviceCodeResponse = client
+        .post("http://127.0.0.1:8081/api/device/code")
         .send()
+        .await?
+        .json()
         .await?;
-    
-    if !response.status().is_success() {
-        eprintln!("❌ Error: Server returned status {}", response.status());
-        eprintln!("   Response: {}", response.text().await?);
-        std::process::exit(1);
+
+    println!("\n👉 Go to: {}", code.verification_uri);
+    println!("   Enter code: {}\n", code.user_code);
+    println!("   (Demo shortcut — approve it yourself with:");
+    println!("    curl -X POST http://127.0.0.1:8081/api/device/approve \\");
+    println!("      -H 'Content-Type: application/json' \\");
+    println!(
+        "      -d '{{\"user_code\": \"{}\", \"user_id\": \"{}\"}}')\n",
+        code.user_code, user_id
+    );
+
+    let mut interval = Duration::from_secs(code.interval);
+    let deadline = Instant::now() + Duration::from_secs(code.expires_in);
+
+    loop {
+        if Instant::now() > deadline {
+            eprintln!("❌ Device code expired before it was approved.");
+            std::process::exit(1);
+        }
+
+        tokio::time::sleep(interval).await;
+
+        let response = client
+            .post("http://127.0.0.1:8081/api/device/token")
+            .header("Content-Type", "application/json")
+            .json(&serde_json::json!({
+                "device_code": code.device_code,
+                "fingerprint": trial_binary::compute_fingerprint(),
+            }))
+            .send()
+            .await?;
+
+        if response.status().is_success() {
+            let license: LicenseResponse = response.json().await?;
+            fs::write("trial.token", &license.token)?;
+
+            println!("✅ License file created successfully!");
+            println!("   trial.token");
+            println!("\n🚀 Now run: cargo run");
+            return Ok(());
+        }
+
+        let err: DeviceErrorResponse = response.json().await?;
+        match err.error.as_str() {
+            "authorization_pending" => continue,
+            "slow_down" => {
+                interval += Duration::from_secs(5);
+                continue;
+            }
+            "expired_token" => {
+                eprintln!("❌ Device code expired before it was approved.");
+                std::process::exit(1);
+            }
+            other => {
+                eprintln!("❌ Error: {}", other);
+                std::process::exit(1);
+            }
+        }
     }
-    
-    let license: LicenseResponse = response.json().await?;
-    
-    // Write files
-    fs::write("trial.token", &license.token)?;
-    fs::write("trial.signature", &license.signature)?;
-    
-    println!("✅ License files created successfully!");
-    println!("   trial.token");
-    println!("   trial.signature");
-    println!("\n🚀 Now run: cargo run");
-    
-    Ok(())
 }
@@ -0,0 +1,25 @@
+// trial-binary/src/lib.rs
+//
+// Shared between the `trial-binary` and `get-license` binaries: computes
+// the machine fingerprint used to node-lock issued tokens.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// Hashes a handful of stable OS/host identifiers into a short
+/// fingerprint. Good enough to node-lock a license to "this machine" for
+/// this demo; a production system would want something sturdier (e.g. a
+/// TPM-backed identifier or a hardware serial).
+pub fn compute_fingerprint() -> String {
+    let hostname = std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .unwrap_or_default();
+    let user = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default();
+
+    let mut hasher = DefaultHasher::new();
+    (hostname, user, std::env::consts::OS, std::env::consts::ARCH).hash(&mut hasher);
+
+    format!("{:016x}", hasher.finish())
+}
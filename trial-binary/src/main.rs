@@ -1,23 +1,72 @@
 // trial-binary/src/main.rs
 // Run with: cargo run
 
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
 use ed25519_dalek::{VerifyingKey, Signature, Verifier};
 use serde::{Deserialize, Serialize};
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::fs;
 
-// 🔑 EMBED YOUR PUBLIC KEY HERE (get it from license server at startup)
-const PUBLIC_KEY_BYTES: [u8; 32] = hex_literal::hex!("b00d8a651dc7702f0e5f3ebc72b3f87aa5e8b8ad482904b9b8954a778ddc6122");
-const GRACE_PERIOD_HOURS: u64 = 24; // Allow 24 hours offline
-const LAST_CHECK_FILE: &str = ".last_license_check";
+// 🔑 EMBED YOUR TRUSTED PUBLIC KEYS HERE (fetch from GET /api/public-key).
+// Keep retired keys in this list after a rotation so tokens issued before
+// the rotation keep verifying; the matching key is picked by the token's
+// `kid` header.
+const TRUSTED_KEYS: &[(&str, &str)] = &[(
+    "legacy",
+    "b00d8a651dc7702f0e5f3ebc72b3f87aa5e8b8ad482904b9b8954a778ddc6122",
+)];
+
+// Caches the server's last signed check receipt. The offline grace
+// deadline (`grace_until`) lives inside the signed payload, not as an
+// editable local integer.
+const CHECK_RECEIPT_FILE: &str = ".license_check_receipt";
+
+// Must mirror the header shape `encode_jwt` produces on the server.
+#[derive(Serialize, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    typ: String,
+    kid: String,
+}
 
 #[derive(Serialize, Deserialize)]
-struct TrialToken {
+struct TrialClaims {
+    sub: String,
+    iat: u64,
+    exp: u64,
+    nbf: u64,
+    jti: String,
+    tier: String,
+    fingerprint: String,
+}
+
+// Mirrors `SignedResponse` on the server: a JSON payload plus a signature
+// over its exact serialized bytes.
+#[derive(Serialize, Deserialize)]
+struct SignedResponse {
+    payload: String,
+    signature: String,
+    kid: String,
+}
+
+#[derive(Deserialize)]
+struct RevocationCheck {
     user_id: String,
+    revoked: bool,
+    checked_at: u64,
+    grace_until: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Crl {
+    revoked_user_ids: Vec<String>,
+    #[allow(dead_code)]
     issued_at: u64,
-    expires_at: u64,
+    next_update: u64,
 }
 
+const CRL_CACHE_FILE: &str = ".trial_crl_cache";
+
 fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -25,104 +74,245 @@ fn current_timestamp() -> u64 {
         .as_secs()
 }
 
-fn verify_trial_token(token_json: &str, signature_hex: &str) -> Result<TrialToken, String> {
-    // 1. Decode public key
-    let verifying_key = VerifyingKey::from_bytes(&PUBLIC_KEY_BYTES)
-        .map_err(|e| format!("❌ Invalid public key: {}", e))?;
-    
-    // 2. Decode signature (trim whitespace first!)
-    let sig_bytes = hex::decode(signature_hex.trim())
+/// Looks up a trusted public key by `kid`, decoding it from `TRUSTED_KEYS`.
+fn trusted_key(kid: &str) -> Result<VerifyingKey, String> {
+    let (_, hex_key) = TRUSTED_KEYS
+        .iter()
+        .find(|(k, _)| *k == kid)
+        .ok_or_else(|| format!("❌ Unknown signing key id: {}", kid))?;
+
+    let key_bytes = hex::decode(hex_key)
+        .map_err(|e| format!("❌ Invalid embedded public key: {}", e))?;
+    let key_array: [u8; 32] = key_bytes
+        .try_into()
+        .map_err(|_| "❌ Embedded public key must be 32 bytes".to_string())?;
+
+    VerifyingKey::from_bytes(&key_array).map_err(|e| format!("❌ Invalid public key: {}", e))
+}
+
+/// Verifies a `SignedResponse` against the embedded trusted keys and
+/// returns its payload bytes, ready to be parsed into the expected type.
+fn verify_signed_response(response: &SignedResponse) -> Result<Vec<u8>, String> {
+    let verifying_key = trusted_key(&response.kid)?;
+
+    let sig_bytes = hex::decode(&response.signature)
         .map_err(|e| format!("❌ Invalid signature hex: {}", e))?;
-    let sig_array: [u8; 64] = sig_bytes.try_into()
+    let sig_array: [u8; 64] = sig_bytes
+        .try_into()
         .map_err(|_| "❌ Signature must be 64 bytes".to_string())?;
     let signature = Signature::from_bytes(&sig_array);
-    
-    // 3. Verify cryptographic signature
-    verifying_key.verify(token_json.as_bytes(), &signature)
+
+    verifying_key
+        .verify(response.payload.as_bytes(), &signature)
+        .map_err(|_| "❌ Server response signature verification failed.".to_string())?;
+
+    Ok(response.payload.clone().into_bytes())
+}
+
+fn verify_trial_token(jwt: &str) -> Result<TrialClaims, String> {
+    // 1. Split into header/payload/signature
+    let parts: Vec<&str> = jwt.split('.').collect();
+    let [header_b64, payload_b64, signature_b64] = parts.as_slice() else {
+        return Err("❌ Invalid token format: expected header.payload.signature".to_string());
+    };
+
+    // 2. Decode and sanity-check the header
+    let header_bytes = URL_SAFE_NO_PAD.decode(*header_b64)
+        .map_err(|e| format!("❌ Invalid header encoding: {}", e))?;
+    let header: JwtHeader = serde_json::from_slice(&header_bytes)
+        .map_err(|e| format!("❌ Invalid header: {}", e))?;
+    if header.alg != "EdDSA" {
+        return Err(format!("❌ Unsupported signing algorithm: {}", header.alg));
+    }
+
+    // 3. Pick the trusted key matching this token's kid, and decode the signature
+    let verifying_key = trusted_key(&header.kid)?;
+    let signature_bytes = URL_SAFE_NO_PAD.decode(*signature_b64)
+        .map_err(|e| format!("❌ Invalid signature encoding: {}", e))?;
+    let sig_array: [u8; 64] = signature_bytes.try_into()
+        .map_err(|_| "❌ Signature must be 64 bytes".to_string())?;
+    let signature = Signature::from_bytes(&sig_array);
+
+    // 4. Verify the signature over "header.payload"
+    let signing_input = format!("{}.{}", header_b64, payload_b64);
+    verifying_key.verify(signing_input.as_bytes(), &signature)
         .map_err(|_| "❌ INVALID TOKEN: Signature verification failed! Token was not issued by authorized license server.".to_string())?;
-    
-    // 4. Parse token
-    let token: TrialToken = serde_json::from_str(token_json)
-        .map_err(|e| format!("❌ Invalid token format: {}", e))?;
-    
-    // 5. Check expiry
+
+    // 5. Parse claims
+    let payload_bytes = URL_SAFE_NO_PAD.decode(*payload_b64)
+        .map_err(|e| format!("❌ Invalid payload encoding: {}", e))?;
+    let claims: TrialClaims = serde_json::from_slice(&payload_bytes)
+        .map_err(|e| format!("❌ Invalid claims: {}", e))?;
+
+    // 6. Check nbf/exp
     let now = current_timestamp();
-    if now > token.expires_at {
-        let days_ago = (now - token.expires_at) / (24 * 60 * 60);
+    if now < claims.nbf {
+        return Err("❌ TOKEN NOT YET VALID: This license isn't active yet.".to_string());
+    }
+    if now > claims.exp {
+        let days_ago = (now - claims.exp) / (24 * 60 * 60);
         return Err(format!(
             "❌ TRIAL EXPIRED: Your trial expired {} days ago. Please contact support to upgrade.",
             days_ago
         ));
     }
-    
+
+    // 7. Check this token is node-locked to this machine
+    let local_fingerprint = trial_binary::compute_fingerprint();
+    if claims.fingerprint != local_fingerprint {
+        return Err("❌ LICENSE NODE-LOCK MISMATCH: This license was issued for a different machine.\n   Run get-license on this machine to activate it here.".to_string());
+    }
+
     // Calculate days remaining
-    let seconds_remaining = token.expires_at - now;
+    let seconds_remaining = claims.exp - now;
     let days_remaining = seconds_remaining / (24 * 60 * 60);
-    
+
     println!("✅ LICENSE VALID");
-    println!("   User: {}", token.user_id);
+    println!("   User: {}", claims.sub);
+    println!("   Tier: {}", claims.tier);
     println!("   Days remaining: {}", days_remaining);
     println!();
-    
-    Ok(token)
+
+    Ok(claims)
 }
 
-fn check_grace_period() -> Result<bool, String> {
-    match fs::read_to_string(LAST_CHECK_FILE) {
-        Ok(content) => {
-            if let Ok(last_check) = content.trim().parse::<u64>() {
-                let now = current_timestamp();
-                let hours_since_check = (now - last_check) / 3600;
-                
-                if hours_since_check <= GRACE_PERIOD_HOURS {
-                    let hours_remaining = GRACE_PERIOD_HOURS - hours_since_check;
-                    println!("   Using offline grace period ({} hours remaining)", hours_remaining);
-                    Ok(true)
-                } else {
-                    Err(format!(
-                        "❌ LICENSE CHECK REQUIRED: Last online check was {} hours ago.\n   Please connect to the internet to verify your license.",
-                        hours_since_check
-                    ))
-                }
-            } else {
-                Err("❌ LICENSE CHECK REQUIRED: Could not read last check timestamp.\n   Please connect to the internet to verify your license.".to_string())
-            }
-        },
-        Err(_) => {
-            Err("❌ LICENSE CHECK REQUIRED: No previous online check found.\n   Please connect to the internet to verify your license.".to_string())
+/// Loads the cached CRL, verifying the signed envelope it was stored in.
+/// Like the check receipt, the cache file holds the full `SignedResponse`
+/// rather than the bare `Crl`, so a hand-edited cache can't forge
+/// `revoked_user_ids`/`next_update`.
+fn load_cached_crl() -> Option<Crl> {
+    let content = fs::read_to_string(CRL_CACHE_FILE).ok()?;
+    let signed: SignedResponse = serde_json::from_str(&content).ok()?;
+    let payload_bytes = verify_signed_response(&signed).ok()?;
+    serde_json::from_slice(&payload_bytes).ok()
+}
+
+/// Fetches the signed CRL from the server and caches the signed envelope
+/// locally, for use the next time we're offline. Best-effort: failures are
+/// swallowed since this runs opportunistically alongside the main
+/// revocation check.
+async fn refresh_crl_cache() {
+    let url = "http://127.0.0.1:8081/api/trial/crl";
+    let Ok(response) = reqwest::get(url).await else {
+        return;
+    };
+    let Ok(signed) = response.json::<SignedResponse>().await else {
+        return;
+    };
+    if verify_signed_response(&signed).is_err() {
+        return;
+    }
+    let _ = fs::write(CRL_CACHE_FILE, serde_json::to_string(&signed).unwrap());
+}
+
+/// Offline fallback: check the cached, signed CRL if we have one. A
+/// revocation it lists is honored even if the CRL has since gone stale
+/// (it was true when signed, and the server never un-revokes a user); but
+/// a stale or missing CRL falls through to the signed check-receipt grace
+/// period instead of hard-failing, so a machine that was online once
+/// still gets its full offline grace window.
+fn check_offline(user_id: &str) -> Result<bool, String> {
+    if let Some(crl) = load_cached_crl() {
+        if crl.revoked_user_ids.iter().any(|id| id == user_id) {
+            return Err("❌ LICENSE REVOKED: Your trial has been revoked by the license server.".to_string());
+        }
+
+        let now = current_timestamp();
+        if now <= crl.next_update {
+            println!("   Using cached, signed revocation list (valid until {})", crl.next_update);
+            return Ok(true);
         }
+        println!("   Cached revocation list is stale; falling back to the offline grace period.");
     }
+
+    check_grace_period(user_id)
+}
+
+/// Validates the last signed check receipt saved by a successful online
+/// check, rejecting it if the signature doesn't verify, it's for a
+/// different user, it records a revocation, it's timestamped in the
+/// future, or its `grace_until` has passed. Unlike a bare local timestamp, none of this is editable by
+/// the user running the binary.
+fn check_grace_period(user_id: &str) -> Result<bool, String> {
+    const REQUIRE_CHECK: &str = "❌ LICENSE CHECK REQUIRED: Please connect to the internet to verify your license.";
+
+    let content = fs::read_to_string(CHECK_RECEIPT_FILE).map_err(|_| REQUIRE_CHECK.to_string())?;
+    let signed: SignedResponse =
+        serde_json::from_str(&content).map_err(|_| REQUIRE_CHECK.to_string())?;
+    let payload_bytes = verify_signed_response(&signed).map_err(|_| {
+        "❌ LICENSE CHECK REQUIRED: Last check receipt failed signature verification.".to_string()
+    })?;
+    let receipt: RevocationCheck =
+        serde_json::from_slice(&payload_bytes).map_err(|_| REQUIRE_CHECK.to_string())?;
+
+    if receipt.user_id != user_id {
+        return Err("❌ LICENSE CHECK REQUIRED: Last check receipt was issued for a different user.".to_string());
+    }
+    if receipt.revoked {
+        return Err("❌ LICENSE REVOKED: Your trial has been revoked by the license server.".to_string());
+    }
+
+    let now = current_timestamp();
+    if receipt.checked_at > now {
+        return Err("❌ LICENSE CHECK REQUIRED: Last check receipt is timestamped in the future.".to_string());
+    }
+    if now > receipt.grace_until {
+        return Err(format!(
+            "❌ LICENSE CHECK REQUIRED: Offline grace period expired at {}.\n   Please connect to the internet to verify your license.",
+            receipt.grace_until
+        ));
+    }
+
+    let seconds_remaining = receipt.grace_until - now;
+    println!("   Using offline grace period ({} hours remaining)", seconds_remaining / 3600);
+    Ok(true)
 }
 
 async fn check_revocation(user_id: &str) -> Result<bool, String> {
     // Check with license server for revocation
     let url = format!("http://127.0.0.1:8081/api/trial/check?user_id={}", user_id);
-    
+
     match reqwest::get(&url).await {
         Ok(response) => {
-            // Online: Check revocation and update last check time
-            match response.json::<serde_json::Value>().await {
-                Ok(data) => {
-                    // Save successful check timestamp
-                    let _ = fs::write(LAST_CHECK_FILE, current_timestamp().to_string());
-                    
-                    if data["revoked"].as_bool().unwrap_or(false) {
+            // Online: verify the signed response, then check revocation
+            // and update last check time
+            match response.json::<SignedResponse>().await {
+                Ok(signed) => {
+                    let payload_bytes = match verify_signed_response(&signed) {
+                        Ok(bytes) => bytes,
+                        Err(e) => return Err(e),
+                    };
+                    let check: RevocationCheck = match serde_json::from_slice(&payload_bytes) {
+                        Ok(c) => c,
+                        Err(e) => return Err(format!("❌ Invalid revocation response: {}", e)),
+                    };
+                    if check.user_id != user_id {
+                        return Err("❌ Revocation response was for a different user.".to_string());
+                    }
+
+                    // Save the signed receipt (used to bound the offline
+                    // grace period) and refresh the cached CRL, for the
+                    // next time we're offline.
+                    let _ = fs::write(CHECK_RECEIPT_FILE, serde_json::to_string(&signed).unwrap());
+                    refresh_crl_cache().await;
+
+                    if check.revoked {
                         Err("❌ LICENSE REVOKED: Your trial has been revoked by the license server.".to_string())
                     } else {
-                        println!("✅ License verified online");
+                        println!("✅ License verified online (signature checked)");
                         Ok(true)
                     }
                 },
                 Err(e) => {
                     println!("⚠️  Warning: Could not parse server response: {}", e);
-                    check_grace_period()
+                    check_offline(user_id)
                 }
             }
         },
         Err(e) => {
-            // Offline: Check if within grace period
+            // Offline: fall back to the cached signed CRL, then the grace period
             println!("⚠️  Warning: Could not reach license server: {}", e);
-            check_grace_period()
+            check_offline(user_id)
         }
     }
 }
@@ -132,9 +322,9 @@ async fn main() {
     println!("╔════════════════════════════════════════╗");
     println!("║     🚀 TRIAL BINARY v1.0.0             ║");
     println!("╚════════════════════════════════════════╝\n");
-    
-    // Load token files (trim whitespace!)
-    let token_json = match fs::read_to_string("trial.token") {
+
+    // Load the token file (trim whitespace!)
+    let token = match fs::read_to_string("trial.token") {
         Ok(content) => content.trim().to_string(),
         Err(_) => {
             eprintln!("❌ ERROR: trial.token file not found!");
@@ -144,43 +334,32 @@ async fn main() {
             std::process::exit(1);
         }
     };
-    
-    let signature_hex = match fs::read_to_string("trial.signature") {
-        Ok(content) => content.trim().to_string(),
-        Err(_) => {
-            eprintln!("❌ ERROR: trial.signature file not found!");
-            eprintln!("   Please obtain a trial license from the license server.\n");
-            eprintln!("   Run this command:");
-            eprintln!("   cargo run --bin get-license -- demo-user\n");
-            std::process::exit(1);
-        }
-    };
-    
+
     // Verify token
-    let token = match verify_trial_token(&token_json, &signature_hex) {
-        Ok(t) => t,
+    let claims = match verify_trial_token(&token) {
+        Ok(c) => c,
         Err(e) => {
             eprintln!("{}\n", e);
             std::process::exit(1);
         }
     };
-    
+
     // Check revocation (online with grace period)
-    if let Err(e) = check_revocation(&token.user_id).await {
+    if let Err(e) = check_revocation(&claims.sub).await {
         eprintln!("{}\n", e);
         std::process::exit(1);
     }
-    
+
     // ✅ License is valid! Run the actual program
     println!("╔════════════════════════════════════════╗");
     println!("║     ✨ LICENSED APPLICATION ✨         ║");
     println!("╚════════════════════════════════════════╝\n");
-    
-    println!("Hello, {}! 👋", token.user_id);
+
+    println!("Hello, {}! 👋", claims.sub);
     println!("Your trial binary is running successfully!");
     println!("\nThis is where your actual application logic would run.");
     println!("Since the license is valid, all features are unlocked.\n");
-    
+
     // Your actual application logic here...
     println!("🎉 Application completed successfully!\n");
 }